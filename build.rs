@@ -1,12 +1,19 @@
 // Copyright 2018-2019 the Deno authors. All rights reserved. MIT license.
 use cargo_gn;
+use sha2::Digest;
+use sha2::Sha256;
 use std::env;
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
 use std::process::Command;
 use which::which;
 
+// Bump this when cutting a new release so the download URL and the cache
+// key for prebuilt archives stay in sync with what's actually published.
+const V8_VERSION: &str = "0.14.0";
+
 fn main() {
   // Detect if trybuild tests are being compiled.
   let is_trybuild = env::var_os("DENO_TRYBUILD").is_some();
@@ -23,11 +30,48 @@ fn main() {
     .map(|s| s.starts_with("rls"))
     .unwrap_or(false);
 
+  let strategy = v8_strategy();
+
   if !(is_trybuild || is_cargo_doc | is_rls) {
-    build_v8()
+    match strategy {
+      V8Strategy::Source => build_v8(),
+      V8Strategy::Download => download_prebuilt_v8(),
+      // Nothing to build: print_link_flags() below points the linker at
+      // the user-provided V8_LIB_LOCATION directly.
+      V8Strategy::System => {}
+    }
   }
   if !(is_cargo_doc || is_rls) {
-    print_link_flags()
+    print_link_flags(strategy)
+  }
+}
+
+#[derive(Clone, Copy)]
+enum V8Strategy {
+  Source,
+  Download,
+  System,
+}
+
+// Mirrors the `ORT_STRATEGY`/`ORT_LIB_LOCATION` scheme from the `ort` crate:
+// `V8_STRATEGY=system` lets CI and distro packagers link against a V8 they
+// already built once, instead of paying for a GN/ninja build per crate.
+fn v8_strategy() -> V8Strategy {
+  match env::var("V8_STRATEGY") {
+    Ok(ref s) if s == "source" => V8Strategy::Source,
+    Ok(ref s) if s == "download" => V8Strategy::Download,
+    Ok(ref s) if s == "system" => V8Strategy::System,
+    Ok(other) => {
+      eprintln!(
+        "unknown V8_STRATEGY '{}': expected 'source', 'download' or 'system'",
+        other
+      );
+      exit(1);
+    }
+    Err(_) if env::var_os("CARGO_FEATURE_DOWNLOAD_BINARIES").is_some() => {
+      V8Strategy::Download
+    }
+    Err(_) => V8Strategy::Source,
   }
 }
 
@@ -37,27 +81,28 @@ fn build_v8() {
   // cargo publish doesn't like pyc files.
   env::set_var("PYTHONDONTWRITEBYTECODE", "1");
 
-  // git submodule update --init --recursive
-  let libcxx_src = PathBuf::from("buildtools/third_party/libc++/trunk/src");
-  if !libcxx_src.is_dir() {
-    eprintln!(
-      "missing source code. Run 'git submodule update --init --recursive'"
-    );
-    exit(1);
-  }
+  let mut finder = Finder::new();
+  sanity_check(&mut finder);
 
-  if need_gn_ninja_download() {
+  if need_gn_ninja_download(&mut finder) {
     download_gn_ninja_binaries();
   }
 
-  // On windows, rustc cannot link with a V8 debug build.
-  let mut gn_args = if cargo_gn::is_debug() && !cfg!(target_os = "windows") {
+  // On windows, rustc cannot link with a V8 debug build. Check the actual
+  // build target, not the host `cfg!(target_os)` -- the build script
+  // itself always compiles for the host, so that would get this backwards
+  // whenever the target differs from the host (see `target_gn_args()`).
+  let target_is_windows =
+    env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows");
+  let mut gn_args = if cargo_gn::is_debug() && !target_is_windows {
     vec!["is_debug=true".to_string()]
   } else {
     vec!["is_debug=false".to_string()]
   };
 
-  if let Some(clang_base_path) = find_compatible_system_clang() {
+  gn_args.extend(target_gn_args());
+
+  if let Some(clang_base_path) = find_compatible_system_clang(&mut finder) {
     println!("clang_base_path {}", clang_base_path.display());
     gn_args.push(format!("clang_base_path={:?}", clang_base_path));
     // TODO: Dedupe this with the one from cc_wrapper()
@@ -91,6 +136,158 @@ fn build_v8() {
   cargo_gn::build("rusty_v8", None);
 }
 
+// Downloads a prebuilt `librusty_v8.a` instead of compiling V8 from source.
+// Selected via the `download-binaries` feature or V8_STRATEGY=download;
+// source builds stay the default so contributors without depot_tools
+// aren't affected.
+fn download_prebuilt_v8() {
+  let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+  let archive_name = prebuilt_archive_name();
+  let cached = prebuilt_cache_dir().join(&archive_name);
+
+  if !cached.exists() {
+    let url = format!(
+      "https://github.com/denoland/rusty_v8/releases/download/v{}/{}",
+      V8_VERSION, archive_name
+    );
+    download_to_file(&url, &cached);
+  }
+  // Re-verify on every use, not just right after downloading: the cache
+  // dir lives under this project's own target/, but a previous build
+  // could have left a stale or tampered file there.
+  verify_prebuilt_checksum(&cached, &archive_name);
+
+  fs::copy(&cached, out_dir.join("librusty_v8.a"))
+    .expect("failed to copy cached librusty_v8.a into OUT_DIR");
+  println!("cargo:rustc-link-search=native={}", out_dir.display());
+}
+
+fn prebuilt_archive_name() -> String {
+  let profile = if cargo_gn::is_debug() { "debug" } else { "release" };
+  let target = env::var("TARGET").unwrap();
+  format!("librusty_v8_{}_{}.a", profile, target)
+}
+
+// Archives are cached under this project's target/ dir (not a shared
+// system temp dir, which other OS users could pre-populate), keyed by
+// version and target, so repeated `cargo build` invocations don't
+// re-download the same file. `cargo clean` removes target/ and the cache
+// with it.
+fn prebuilt_cache_dir() -> PathBuf {
+  let root = env::current_dir().unwrap();
+  let out_dir = env::var_os("OUT_DIR").unwrap();
+  let out_dir_abs = root.join(out_dir);
+  // This would be target/debug or target/release
+  let target_dir = out_dir_abs
+    .parent()
+    .unwrap()
+    .parent()
+    .unwrap()
+    .parent()
+    .unwrap();
+  let dir = target_dir.join("rusty_v8_prebuilt_cache").join(V8_VERSION);
+  fs::create_dir_all(&dir).expect("failed to create prebuilt V8 cache dir");
+  dir
+}
+
+fn download_to_file(url: &str, dest: &Path) {
+  println!("cargo:warning=Downloading {}", url);
+  let resp = ureq::get(url).call().unwrap_or_else(|e| {
+    eprintln!("failed to download {}: {}", url, e);
+    exit(1);
+  });
+  let mut file =
+    fs::File::create(dest).expect("failed to create prebuilt V8 archive file");
+  std::io::copy(&mut resp.into_reader(), &mut file)
+    .expect("failed to write downloaded V8 archive");
+}
+
+fn verify_prebuilt_checksum(path: &Path, archive_name: &str) {
+  let url = format!(
+    "https://github.com/denoland/rusty_v8/releases/download/v{}/{}.sha256",
+    V8_VERSION, archive_name
+  );
+  let resp = ureq::get(&url).call().unwrap_or_else(|e| {
+    eprintln!("failed to download checksum for {}: {}", archive_name, e);
+    exit(1);
+  });
+  let expected = resp
+    .into_string()
+    .expect("checksum response was not valid utf-8")
+    .split_whitespace()
+    .next()
+    .expect("checksum response was empty")
+    .to_lowercase();
+
+  let actual = sha256_hex(path);
+  if actual != expected {
+    let _ = fs::remove_file(path);
+    eprintln!(
+      "checksum mismatch for {}: expected {}, got {} -- refusing to link a corrupt V8 archive",
+      archive_name, expected, actual
+    );
+    exit(1);
+  }
+}
+
+fn sha256_hex(path: &Path) -> String {
+  let bytes = fs::read(path).expect("failed to read downloaded file");
+  let mut hasher = Sha256::new();
+  hasher.update(&bytes);
+  format!("{:x}", hasher.finalize())
+}
+
+// Translates the Cargo target triple into the GN args that select what V8
+// is actually compiled for. `platform()` below stays host-only: it picks
+// the gn/ninja/clang binaries that *run* the build, which always matches
+// the host even when V8 itself is being cross-compiled.
+fn target_gn_args() -> Vec<String> {
+  let target_arch = env::var("CARGO_CFG_TARGET_ARCH")
+    .expect("CARGO_CFG_TARGET_ARCH not set by cargo");
+  let target_os = env::var("CARGO_CFG_TARGET_OS")
+    .expect("CARGO_CFG_TARGET_OS not set by cargo");
+
+  let target_cpu = match target_arch.as_str() {
+    "x86_64" => "x64",
+    "aarch64" => "arm64",
+    "arm" => "arm",
+    "x86" => "x86",
+    other => {
+      eprintln!("unsupported CARGO_CFG_TARGET_ARCH: {}", other);
+      exit(1);
+    }
+  };
+
+  let gn_target_os = match target_os.as_str() {
+    "linux" => "linux",
+    "macos" => "mac",
+    "windows" => "win",
+    "android" => "android",
+    other => {
+      eprintln!("unsupported CARGO_CFG_TARGET_OS: {}", other);
+      exit(1);
+    }
+  };
+
+  let mut args = vec![
+    format!("target_cpu=\"{}\"", target_cpu),
+    format!("target_os=\"{}\"", gn_target_os),
+  ];
+
+  if gn_target_os == "android" {
+    let ndk_root = env::var("ANDROID_NDK_ROOT").unwrap_or_else(|_| {
+      eprintln!("ANDROID_NDK_ROOT must be set when targeting android");
+      exit(1);
+    });
+    let api_level =
+      env::var("ANDROID_API_LEVEL").unwrap_or_else(|_| "24".to_string());
+    args.push(format!("android_ndk_root={:?}", ndk_root));
+    args.push(format!("android_api_level={}", api_level));
+  }
+
+  args
+}
+
 fn platform() -> &'static str {
   #[cfg(target_os = "windows")]
   {
@@ -106,6 +303,8 @@ fn platform() -> &'static str {
   }
 }
 
+// Downloads gn/ninja for the host (see `platform()`): these tools run the
+// build, they don't end up in the output, so they're never cross-compiled.
 fn download_gn_ninja_binaries() {
   let root = env::current_dir().unwrap();
   // target/debug//build/rusty_v8-d9e5a424d4f96994/out/
@@ -139,11 +338,86 @@ fn download_gn_ninja_binaries() {
   }
   assert!(gn.exists());
   assert!(ninja.exists());
+  verify_tool_checksum("gn", &gn);
+  verify_tool_checksum("ninja", &ninja);
   env::set_var("GN", gn);
   env::set_var("NINJA", ninja);
 }
 
-fn print_link_flags() {
+// Expected SHA-256 digests of the gn/ninja/clang downloads, keyed by
+// `(tool, platform())`. Starts empty: an unpinned `(tool, platform())` pair
+// only warns (see `verify_tool_checksum`) and prints the observed digest,
+// so the first build after pinning a new revision in
+// tools/gn_ninja_binaries.py / tools/clang/scripts/update.py tells you
+// exactly what to add here. Once a platform's digest is known, add it so
+// that pair starts being hard-enforced.
+const TOOL_CHECKSUMS: &[(&str, &str, &str)] = &[];
+
+// Verifies `path` (a downloaded gn/ninja/clang binary) against the pinned
+// digest for `tool` on the current `platform()`. `V8_SKIP_CHECKSUM` is an
+// escape hatch for developers intentionally rolling a new, not-yet-pinned
+// tool revision.
+//
+// `TOOL_CHECKSUMS` has no entries yet -- pinning real digests requires
+// actually downloading the gn/ninja/clang revisions that
+// tools/gn_ninja_binaries.py and tools/clang/scripts/update.py fetch and
+// hashing them, which needs network access this change was made without.
+// Until it's populated, an unpinned `(tool, platform())` only warns; set
+// `V8_REQUIRE_PINNED_CHECKSUMS=1` (e.g. in CI, once the manifest above is
+// filled in for that platform) to turn a missing entry into a hard
+// failure instead of silently trusting the download.
+fn verify_tool_checksum(tool: &str, path: &Path) {
+  if env::var_os("V8_SKIP_CHECKSUM").is_some() {
+    println!("cargo:warning=V8_SKIP_CHECKSUM set, skipping checksum for {}", tool);
+    return;
+  }
+
+  let actual = sha256_hex(path);
+  match TOOL_CHECKSUMS
+    .iter()
+    .find(|(t, p, _)| *t == tool && *p == platform())
+  {
+    Some((_, _, expected)) if *expected == actual => {}
+    Some((_, _, expected)) => {
+      eprintln!(
+        "checksum mismatch for {} ({}): expected {}, got {} -- set V8_SKIP_CHECKSUM=1 to bypass if this is an intentional tool upgrade",
+        tool, platform(), expected, actual
+      );
+      exit(1);
+    }
+    None if env::var_os("V8_REQUIRE_PINNED_CHECKSUMS").is_some() => {
+      eprintln!(
+        "no pinned checksum for {} on {} (observed sha256={}) and V8_REQUIRE_PINNED_CHECKSUMS is set -- add it to TOOL_CHECKSUMS",
+        tool, platform(), actual
+      );
+      exit(1);
+    }
+    None => {
+      println!(
+        "cargo:warning=no pinned checksum for {} on {}, observed sha256={} (add it to TOOL_CHECKSUMS)",
+        tool, platform(), actual
+      );
+    }
+  }
+}
+
+fn print_link_flags(strategy: V8Strategy) {
+  if let V8Strategy::System = strategy {
+    let dir = env::var("V8_LIB_LOCATION").unwrap_or_else(|_| {
+      eprintln!("V8_STRATEGY=system requires V8_LIB_LOCATION to point at a directory containing librusty_v8.a");
+      exit(1);
+    });
+    let dir = PathBuf::from(dir);
+    if !dir.join("librusty_v8.a").exists() {
+      eprintln!(
+        "V8_LIB_LOCATION {} does not contain librusty_v8.a",
+        dir.display()
+      );
+      exit(1);
+    }
+    println!("cargo:rustc-link-search=native={}", dir.display());
+  }
+
   println!("cargo:rustc-link-lib=static=rusty_v8");
 
   if cfg!(target_os = "windows") {
@@ -152,9 +426,118 @@ fn print_link_flags() {
   }
 }
 
-fn need_gn_ninja_download() -> bool {
-  !((which("ninja").is_ok() || env::var_os("NINJA").is_some())
-    && env::var_os("GN").is_some())
+fn need_gn_ninja_download(finder: &mut Finder) -> bool {
+  !(finder.find("NINJA", "ninja").is_some() && env::var_os("GN").is_some())
+}
+
+// A small cached `which`-style resolver, modeled on rustbuild's
+// `sanity::Finder`. Avoids re-running `which`/stat-ing the same binary
+// from `need_gn_ninja_download`, `sanity_check`, and anywhere else that
+// needs to know whether a tool is available.
+struct Finder {
+  cache: std::collections::HashMap<String, Option<PathBuf>>,
+}
+
+impl Finder {
+  fn new() -> Finder {
+    Finder {
+      cache: std::collections::HashMap::new(),
+    }
+  }
+
+  // Resolves `binary`, preferring the path in `env_var` (if set) over
+  // searching PATH with `which`.
+  fn find(&mut self, env_var: &str, binary: &str) -> Option<PathBuf> {
+    if let Some(found) = self.cache.get(binary) {
+      return found.clone();
+    }
+    let found = env::var_os(env_var)
+      .map(PathBuf::from)
+      .or_else(|| which(binary).ok());
+    self.cache.insert(binary.to_string(), found.clone());
+    found
+  }
+
+  // Resolves `CLANG_BASE_PATH` to a usable clang install, if one is set
+  // and its `bin/clang` is new enough. Shared by `sanity_check` and
+  // `find_compatible_system_clang` so the version check only runs once.
+  fn system_clang(&mut self) -> Option<PathBuf> {
+    if let Some(found) = self.cache.get("clang_base_path") {
+      return found.clone();
+    }
+    let found = env::var("CLANG_BASE_PATH").ok().and_then(|p| {
+      let base_path = PathBuf::from(p);
+      let clang_path = base_path.join("bin").join("clang");
+      if is_compatible_clang_version(&clang_path) {
+        Some(base_path)
+      } else {
+        None
+      }
+    });
+    self
+      .cache
+      .insert("clang_base_path".to_string(), found.clone());
+    found
+  }
+}
+
+// Checks every external prerequisite `build_v8()` needs before committing
+// to a multi-minute GN/ninja run, collecting *all* problems so a fresh
+// clone gets one consolidated error instead of discovering missing
+// prerequisites one rebuild at a time.
+fn sanity_check(finder: &mut Finder) {
+  let mut problems = Vec::new();
+
+  // git submodule update --init --recursive
+  let libcxx_src = PathBuf::from("buildtools/third_party/libc++/trunk/src");
+  if !libcxx_src.is_dir() {
+    problems.push(
+      "missing libc++ source -- run 'git submodule update --init --recursive'"
+        .to_string(),
+    );
+  }
+
+  // The gn/ninja and clang download scripts are run as `python` from PATH.
+  if which("python").is_err() {
+    problems.push(
+      "no 'python' found on PATH -- install Python and add it to PATH"
+        .to_string(),
+    );
+  }
+
+  if let Ok(gn) = env::var("GN") {
+    if !Path::new(&gn).exists() {
+      problems.push(format!(
+        "GN={} does not exist -- fix the path, or unset GN to let rusty_v8 download it",
+        gn
+      ));
+    }
+  }
+  if let Ok(ninja) = env::var("NINJA") {
+    if !Path::new(&ninja).exists() {
+      problems.push(format!(
+        "NINJA={} does not exist -- fix the path, or unset NINJA to let rusty_v8 download it",
+        ninja
+      ));
+    }
+  }
+
+  if let Ok(p) = env::var("CLANG_BASE_PATH") {
+    if finder.system_clang().is_none() {
+      problems.push(format!(
+        "CLANG_BASE_PATH={} does not contain a clang new enough to build V8 -- fix the path, or unset CLANG_BASE_PATH to let rusty_v8 download one",
+        p
+      ));
+    }
+  }
+
+  if !problems.is_empty() {
+    eprintln!("rusty_v8 build sanity check failed:");
+    for problem in &problems {
+      eprintln!("  - {}", problem);
+    }
+    exit(1);
+  }
 }
 
 // Chromiums gn arg clang_base_path is currently compatible with:
@@ -164,23 +547,43 @@ fn need_gn_ninja_download() -> bool {
 // but unfortunately it doesn't work with version-suffixed packages commonly
 // found in Linux packet managers
 fn is_compatible_clang_version(clang_path: &Path) -> bool {
-  if let Ok(o) = Command::new(clang_path).arg("--version").output() {
-    let _output = String::from_utf8(o.stdout).unwrap();
-    // TODO check version output to make sure it's supported.
-    const _MIN_APPLE_CLANG_VER: f32 = 11.0;
-    const _MIN_LLVM_CLANG_VER: f32 = 8.0;
-    return true;
+  const MIN_APPLE_CLANG_VER: (u32, u32) = (11, 0);
+  const MIN_LLVM_CLANG_VER: (u32, u32) = (8, 0);
+
+  let output = match Command::new(clang_path).arg("--version").output() {
+    Ok(o) => o,
+    Err(_) => return false,
+  };
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  match parse_clang_version(&stdout) {
+    Some((true, ver)) => ver >= MIN_APPLE_CLANG_VER,
+    Some((false, ver)) => ver >= MIN_LLVM_CLANG_VER,
+    None => false,
   }
-  false
 }
 
-fn find_compatible_system_clang() -> Option<PathBuf> {
-  if let Ok(p) = env::var("CLANG_BASE_PATH") {
-    let base_path = Path::new(&p);
-    let clang_path = base_path.join("bin").join("clang");
-    if is_compatible_clang_version(&clang_path) {
-      return Some(base_path.to_path_buf());
-    }
+// Parses `clang --version` output (first occurrence of the marker), returning
+// `(is_apple_clang, (major, minor))`. Handles both Apple's
+// "Apple clang version X.Y" and upstream LLVM's "clang version X.Y".
+fn parse_clang_version(version_output: &str) -> Option<(bool, (u32, u32))> {
+  let (is_apple, marker) = if version_output.contains("Apple clang version") {
+    (true, "Apple clang version ")
+  } else if version_output.contains("clang version") {
+    (false, "clang version ")
+  } else {
+    return None;
+  };
+
+  let rest = &version_output[version_output.find(marker)? + marker.len()..];
+  let mut parts = rest.splitn(3, |c: char| c == '.' || c.is_whitespace());
+  let major = parts.next()?.parse().ok()?;
+  let minor = parts.next()?.parse().ok()?;
+  Some((is_apple, (major, minor)))
+}
+
+fn find_compatible_system_clang(finder: &mut Finder) -> Option<PathBuf> {
+  if let Some(base_path) = finder.system_clang() {
+    return Some(base_path);
   }
 
   println!("using Chromiums clang");
@@ -188,7 +591,9 @@ fn find_compatible_system_clang() -> Option<PathBuf> {
 }
 
 // Download chromium's clang into OUT_DIR because Cargo will not allow us to
-// modify the source directory.
+// modify the source directory. Like gn/ninja, this is the host compiler
+// `platform()` refers to; it cross-compiles for `target_gn_args()` when the
+// two differ.
 fn clang_download() -> PathBuf {
   let root = env::current_dir().unwrap();
   // target/debug//build/rusty_v8-d9e5a424d4f96994/out/
@@ -211,6 +616,7 @@ fn clang_download() -> PathBuf {
     .expect("clang download failed");
   assert!(status.success());
   assert!(clang_base_path.exists());
+  verify_tool_checksum("clang", &clang_base_path.join("bin").join("clang"));
   clang_base_path
 }
 
@@ -222,3 +628,49 @@ fn cc_wrapper(gn_args: &mut Vec<String>, sccache_path: &Path) {
     gn_args.push("treat_warnings_as_errors=false".to_string());
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::parse_clang_version;
+
+  #[test]
+  fn apple_clang_11() {
+    let out = "Apple clang version 11.0.0 (clang-1100.0.33.8)\nTarget: x86_64-apple-darwin19.6.0\n";
+    assert_eq!(parse_clang_version(out), Some((true, (11, 0))));
+  }
+
+  #[test]
+  fn apple_clang_15() {
+    let out = "Apple clang version 15.0.0 (clang-1500.1.0.2.5)\nTarget: arm64-apple-darwin23.0.0\n";
+    assert_eq!(parse_clang_version(out), Some((true, (15, 0))));
+  }
+
+  #[test]
+  fn upstream_clang_7() {
+    let out = "clang version 7.0.1-8 (tags/RELEASE_701/final)\nTarget: x86_64-pc-linux-gnu\n";
+    assert_eq!(parse_clang_version(out), Some((false, (7, 0))));
+  }
+
+  #[test]
+  fn upstream_clang_8() {
+    let out = "clang version 8.0.0-3 (tags/RELEASE_800/final)\nTarget: x86_64-pc-linux-gnu\n";
+    assert_eq!(parse_clang_version(out), Some((false, (8, 0))));
+  }
+
+  #[test]
+  fn upstream_clang_17() {
+    let out = "clang version 17.0.6\nTarget: x86_64-pc-linux-gnu\n";
+    assert_eq!(parse_clang_version(out), Some((false, (17, 0))));
+  }
+
+  #[test]
+  fn homebrew_llvm_16() {
+    let out = "clang version 16.0.6\nTarget: x86_64-apple-darwin23.0.0\nInstalledDir: /usr/local/opt/llvm/bin\n";
+    assert_eq!(parse_clang_version(out), Some((false, (16, 0))));
+  }
+
+  #[test]
+  fn unparseable_output() {
+    assert_eq!(parse_clang_version("not a clang at all"), None);
+  }
+}